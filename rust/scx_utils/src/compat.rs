@@ -5,11 +5,13 @@
 
 use anyhow::{anyhow, bail, Context, Result};
 use libbpf_rs::libbpf_sys::*;
+use std::collections::HashMap;
 use std::ffi::c_void;
 use std::ffi::CStr;
 use std::ffi::CString;
 use std::mem::size_of;
 use std::slice::from_raw_parts;
+use std::sync::Mutex;
 
 lazy_static::lazy_static! {
     pub static ref SCX_OPS_SWITCH_PARTIAL: u64 =
@@ -126,6 +128,111 @@ pub fn struct_has_field(type_name: &str, field: &str) -> Result<bool> {
     return Ok(false);
 }
 
+/// Return whether a BTF type id refers to a `BTF_KIND_FUNC` named `name`.
+fn is_func_named(btf: &btf, tid: u32, name: &str) -> Result<bool> {
+    let t = unsafe { btf__type_by_id(btf, tid) };
+    if t.is_null() {
+        return Ok(false);
+    }
+    let t = unsafe { &*t };
+
+    Ok(btf_kind(t) == BTF_KIND_FUNC && btf_name_str_by_offset(btf, t.name_off)? == name)
+}
+
+/// Return whether a kfunc named `name` exists in vmlinux BTF.
+///
+/// This is used to probe for kfuncs that only appeared in later kernels, e.g.
+/// `scx_bpf_select_cpu_dfl`, so callers can fall back gracefully on older ones instead of
+/// simply failing to load.
+///
+/// Plain `BTF_KIND_FUNC` declarations aren't enough to identify a kfunc: vmlinux BTF carries a
+/// `BTF_KIND_FUNC` entry for every globally visible kernel function, kfunc or not. The kernel's
+/// `__bpf_kfunc` macro additionally tags a function with a `BTF_KIND_DECL_TAG` named
+/// `"bpf_kfunc"`, so require that tag (pointing at a `BTF_KIND_FUNC` named `name`) to match.
+pub fn kfunc_exists(name: &str) -> Result<bool> {
+    let btf: &btf = *VMLINUX_BTF;
+
+    let nr_types = unsafe { btf__type_cnt(btf) };
+    for tid in 1..nr_types {
+        let t = unsafe { btf__type_by_id(btf, tid) };
+        if t.is_null() {
+            continue;
+        }
+        let t = unsafe { &*t };
+
+        if btf_kind(t) != BTF_KIND_DECL_TAG {
+            continue;
+        }
+        if btf_name_str_by_offset(btf, t.name_off)? != "bpf_kfunc" {
+            continue;
+        }
+
+        let target_id = unsafe { t.__bindgen_anon_1.type_ };
+        if is_func_named(btf, target_id, name)? {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+lazy_static::lazy_static! {
+    static ref FEATURE_CACHE: Mutex<HashMap<&'static str, bool>> = Mutex::new(HashMap::new());
+}
+
+/// How a [`Feature`] is probed against vmlinux BTF.
+enum Probe {
+    /// Must exist as a kfunc (see [`kfunc_exists`]).
+    Kfunc(&'static str),
+    /// Must exist as an enumerator of the given enum type (see [`read_enum`]).
+    Enum(&'static str, &'static str),
+}
+
+/// Optional kernel features that schedulers may want to branch on, memoized for the lifetime
+/// of the process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Feature {
+    /// `scx_bpf_select_cpu_dfl()`, used to fall back to the default idle CPU selection logic.
+    SelectCpuDfl,
+    /// `SCX_SLICE_INF`, the infinite time slice that centralized dispatch relies on so a single
+    /// CPU can dispatch into every other CPU's local DSQ without them re-entering the
+    /// scheduler on their own.
+    CentralDispatch,
+}
+
+impl Feature {
+    fn probe(&self) -> Probe {
+        match self {
+            Feature::SelectCpuDfl => Probe::Kfunc("scx_bpf_select_cpu_dfl"),
+            Feature::CentralDispatch => Probe::Enum("scx_public_consts", "SCX_SLICE_INF"),
+        }
+    }
+
+    fn cache_key(&self) -> &'static str {
+        match self.probe() {
+            Probe::Kfunc(name) => name,
+            Probe::Enum(_, name) => name,
+        }
+    }
+
+    /// Check whether the running kernel supports this feature, probing vmlinux BTF the first
+    /// time and returning the cached result on every subsequent call.
+    pub fn enabled(&self) -> bool {
+        let key = self.cache_key();
+
+        if let Some(&enabled) = FEATURE_CACHE.lock().unwrap().get(key) {
+            return enabled;
+        }
+
+        let enabled = match self.probe() {
+            Probe::Kfunc(name) => kfunc_exists(name).unwrap_or(false),
+            Probe::Enum(type_name, name) => read_enum(type_name, name).is_ok(),
+        };
+        FEATURE_CACHE.lock().unwrap().insert(key, enabled);
+        enabled
+    }
+}
+
 /// struct sched_ext_ops can change over time. If
 /// compat.bpf.h::SCX_OPS_DEFINE() is used to define ops and scx_ops_load!()
 /// and scx_ops_attach!() are used to load and attach it, backward
@@ -177,4 +284,31 @@ mod tests {
         assert!(!super::struct_has_field("task_struct", "NO_SUCH_FIELD").unwrap());
         assert!(super::struct_has_field("NO_SUCH_STRUCT", "NO_SUCH_FIELD").is_err());
     }
+
+    #[test]
+    fn test_kfunc_exists() {
+        assert!(super::kfunc_exists("bpf_rcu_read_lock").unwrap());
+        // An ordinary, non-kfunc global function must NOT match, even though it has its own
+        // BTF_KIND_FUNC entry like any real kfunc does.
+        assert!(!super::kfunc_exists("vprintk").unwrap());
+        assert!(!super::kfunc_exists("NO_SUCH_KFUNC").unwrap());
+    }
+
+    #[test]
+    fn test_feature_enabled_is_memoized() {
+        let expected = super::kfunc_exists("scx_bpf_select_cpu_dfl").unwrap_or(false);
+
+        // The memoized result must agree with a direct, uncached probe, both on the first
+        // call (which populates the cache) and the second (which hits it).
+        assert_eq!(super::Feature::SelectCpuDfl.enabled(), expected);
+        assert_eq!(super::Feature::SelectCpuDfl.enabled(), expected);
+    }
+
+    #[test]
+    fn test_central_dispatch_feature_probes_slice_inf_enum() {
+        let expected = super::read_enum("scx_public_consts", "SCX_SLICE_INF").is_ok();
+
+        assert_eq!(super::Feature::CentralDispatch.enabled(), expected);
+        assert_eq!(super::Feature::CentralDispatch.enabled(), expected);
+    }
 }
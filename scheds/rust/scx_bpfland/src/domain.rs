@@ -0,0 +1,150 @@
+// SPDX-License-Identifier: GPL-2.0
+//
+// Copyright (c) 2024 Andrea Righi <righi.andrea@gmail.com>.
+
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+//! L3-cache-aware scheduling domains.
+//!
+//! A [`Topology`] groups CPUs that share a last-level cache into
+//! [`Domain`]s. The BPF side only ever schedules within a domain (one DSQ
+//! per domain); cross-domain balancing is handled entirely from
+//! userspace in `Scheduler::run()`.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use anyhow::Context;
+use anyhow::Result;
+
+/// A group of CPUs that share an L3 cache.
+#[derive(Debug, Clone)]
+pub struct Domain {
+    pub id: u32,
+    pub cpus: Vec<u32>,
+}
+
+/// Maps every online CPU to the domain it belongs to.
+#[derive(Debug, Clone)]
+pub struct Topology {
+    pub domains: Vec<Domain>,
+    pub cpu_domain: BTreeMap<u32, u32>,
+}
+
+impl Topology {
+    /// Build a single, flat domain spanning all online CPUs.
+    ///
+    /// Used when `--disable-topology` is passed, giving back today's
+    /// behavior of one global scheduling domain.
+    pub fn flat(nr_cpus: usize) -> Self {
+        let cpus: Vec<u32> = (0..nr_cpus as u32).collect();
+        let cpu_domain = cpus.iter().map(|&cpu| (cpu, 0)).collect();
+
+        Topology {
+            domains: vec![Domain { id: 0, cpus }],
+            cpu_domain,
+        }
+    }
+
+    /// Discover L3 domains by walking
+    /// `/sys/devices/system/cpu/cpuN/cache/indexM/{level,shared_cpu_list}`.
+    ///
+    /// CPUs whose L3 `shared_cpu_list` matches are grouped into the same
+    /// domain; a CPU with no level-3 cache entry (e.g. some VMs) is put in
+    /// a domain of its own.
+    pub fn from_sysfs() -> Result<Self> {
+        let cpu_root = Path::new("/sys/devices/system/cpu");
+        let mut cpu_ids: Vec<u32> = fs::read_dir(cpu_root)
+            .context("Failed to read /sys/devices/system/cpu")?
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                entry
+                    .file_name()
+                    .into_string()
+                    .ok()?
+                    .strip_prefix("cpu")?
+                    .parse::<u32>()
+                    .ok()
+            })
+            .collect();
+        cpu_ids.sort_unstable();
+
+        let mut groups: Vec<Vec<u32>> = Vec::new();
+        let mut assigned: BTreeMap<u32, usize> = BTreeMap::new();
+
+        for cpu in &cpu_ids {
+            if assigned.contains_key(cpu) {
+                continue;
+            }
+
+            let shared = Self::l3_shared_cpus(*cpu).unwrap_or_else(|| vec![*cpu]);
+            let group_idx = groups.len();
+            for sibling in &shared {
+                assigned.insert(*sibling, group_idx);
+            }
+            groups.push(shared);
+        }
+
+        let mut cpu_domain = BTreeMap::new();
+        let domains = groups
+            .into_iter()
+            .enumerate()
+            .map(|(id, mut cpus)| {
+                cpus.sort_unstable();
+                for &cpu in &cpus {
+                    cpu_domain.insert(cpu, id as u32);
+                }
+                Domain { id: id as u32, cpus }
+            })
+            .collect();
+
+        Ok(Topology { domains, cpu_domain })
+    }
+
+    /// Return the CPUs sharing an L3 cache with `cpu`, if any.
+    fn l3_shared_cpus(cpu: u32) -> Option<Vec<u32>> {
+        let cache_dir = format!("/sys/devices/system/cpu/cpu{}/cache", cpu);
+        let entries = fs::read_dir(&cache_dir).ok()?;
+
+        for entry in entries.filter_map(|e| e.ok()) {
+            let index_dir = entry.path();
+
+            let level: u32 = fs::read_to_string(index_dir.join("level"))
+                .ok()?
+                .trim()
+                .parse()
+                .ok()?;
+            if level != 3 {
+                continue;
+            }
+
+            let shared_cpu_list = fs::read_to_string(index_dir.join("shared_cpu_list")).ok()?;
+            return Some(parse_cpu_list(shared_cpu_list.trim()));
+        }
+
+        None
+    }
+
+    pub fn nr_domains(&self) -> usize {
+        self.domains.len()
+    }
+}
+
+/// Parse a Linux CPU list such as `"0-3,8,10-11"` into individual CPU ids.
+fn parse_cpu_list(list: &str) -> Vec<u32> {
+    let mut cpus = Vec::new();
+
+    for range in list.split(',').filter(|s| !s.is_empty()) {
+        if let Some((start, end)) = range.split_once('-') {
+            if let (Ok(start), Ok(end)) = (start.parse::<u32>(), end.parse::<u32>()) {
+                cpus.extend(start..=end);
+            }
+        } else if let Ok(cpu) = range.parse::<u32>() {
+            cpus.push(cpu);
+        }
+    }
+
+    cpus
+}
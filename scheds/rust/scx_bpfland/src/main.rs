@@ -9,6 +9,10 @@ mod bpf_skel;
 pub use bpf_skel::*;
 pub mod bpf_intf;
 pub use bpf_intf::*;
+mod domain;
+use domain::Topology;
+mod stats_server;
+use stats_server::{StatsServer, StatsSnapshot, Tunable};
 
 use std::fs::File;
 use std::io::Read;
@@ -24,6 +28,7 @@ use anyhow::bail;
 use anyhow::Context;
 use anyhow::Result;
 use clap::Parser;
+use clap::ValueEnum;
 use log::info;
 
 use metrics::{gauge, Gauge};
@@ -47,6 +52,39 @@ use scx_utils::UserExitInfo;
 
 const SCHEDULER_NAME: &'static str = "scx_bpfland";
 
+/// Performance/power tradeoff driving the per-CPU utilization hints published to schedutil.
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum PowerProfile {
+    /// Scale reported utilization down, favoring lower CPU frequencies.
+    Powersave,
+    /// Report utilization as measured.
+    Balanced,
+    /// Scale reported utilization up, favoring higher CPU frequencies.
+    Performance,
+}
+
+impl PowerProfile {
+    /// Multiplier (in percent) applied to the raw per-CPU utilization estimate before it is
+    /// published for schedutil to act on.
+    fn utilization_pct(&self) -> u64 {
+        match self {
+            PowerProfile::Powersave => 75,
+            PowerProfile::Balanced => 100,
+            PowerProfile::Performance => 125,
+        }
+    }
+}
+
+impl std::fmt::Display for PowerProfile {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            PowerProfile::Powersave => write!(f, "powersave"),
+            PowerProfile::Balanced => write!(f, "balanced"),
+            PowerProfile::Performance => write!(f, "performance"),
+        }
+    }
+}
+
 /// scx_bpfland: a vruntime-based sched_ext scheduler that prioritizes interactive workloads.
 ///
 /// This scheduler is derived from scx_rustland, but it is fully implemented in BFP with minimal
@@ -109,8 +147,41 @@ struct Opts {
     /// Print scheduler version and exit.
     #[clap(short = 'V', long, action = clap::ArgAction::SetTrue)]
     version: bool,
+
+    /// Disable L3-cache-aware scheduling domains and fall back to a single flat domain.
+    #[clap(long, action = clap::ArgAction::SetTrue)]
+    disable_topology: bool,
+
+    /// Performance/power profile used to drive cpufreq (via schedutil) utilization hints.
+    #[clap(long, value_enum, default_value_t = PowerProfile::Balanced)]
+    cpufreq: PowerProfile,
+
+    /// Path of a Unix domain socket serving live stats and accepting tunable updates
+    /// (e.g. `slice_us=4000`) without requiring a restart.
+    #[clap(long)]
+    stats_socket: Option<String>,
+
+    /// Enable centralized dispatch: a single CPU makes all scheduling decisions and
+    /// dispatches into per-CPU local DSQs, leaving every other CPU free of scheduler-clock
+    /// overhead (at the cost of throughput on the central CPU).
+    #[clap(long, action = clap::ArgAction::SetTrue)]
+    central: bool,
+
+    /// CPU used for centralized dispatch when `--central` is enabled.
+    #[clap(long, default_value = "0")]
+    central_cpu: u32,
 }
 
+/// Maximum number of tasks migrated from an overloaded domain to an underloaded one per
+/// balancing tick, keeping userspace balancing overhead O(1) regardless of system load.
+const MAX_DOMAIN_MIGRATIONS: usize = 32;
+
+/// Maximum number of L3-cache domains the BPF side can track (must match `MAX_DOMAINS` in
+/// `bpf/intf.h`): domain ids double as DSQ ids and `domain_load`/`domain_recent_pids` are fixed
+/// `[MAX_DOMAINS]` arrays, so a discovered topology with more domains than this would both
+/// collide with `SHARED_DSQ_ID` and index those arrays out of bounds.
+const MAX_DOMAINS: usize = 64;
+
 struct Metrics {
     nr_running: Gauge,
     nr_interactive: Gauge,
@@ -118,6 +189,9 @@ struct Metrics {
     nr_direct_dispatches: Gauge,
     nr_prio_dispatches: Gauge,
     nr_shared_dispatches: Gauge,
+    avg_freq_khz: Gauge,
+    nr_central_dispatches: Gauge,
+    nr_central_mismatches: Gauge,
 }
 
 impl Metrics {
@@ -141,10 +215,51 @@ impl Metrics {
             nr_shared_dispatches: gauge!(
                 "nr_shared_dispatches", "info" => "Number of regular task dispatches"
             ),
+            avg_freq_khz: gauge!(
+                "avg_freq_khz", "info" => "Average effective CPU frequency in kHz"
+            ),
+            nr_central_dispatches: gauge!(
+                "nr_central_dispatches", "info" => "Number of dispatches made by the central CPU"
+            ),
+            nr_central_mismatches: gauge!(
+                "nr_central_mismatches", "info" =>
+                    "Number of central dispatches that spilled to the fallback DSQ due to a full local DSQ"
+            ),
         }
     }
 }
 
+/// Write `tunables` into the single-element `tunables_map`, the writable BPF map the scheduler
+/// actually reads its live-tunable parameters from.
+fn update_tunables_map(skel: &BpfSkel, tunables: &bpf_intf::bpfland_tunables) -> Result<()> {
+    let key = 0u32;
+    // SAFETY: `bpfland_tunables` is a plain, repr(C) struct shared with intf.h; the map value
+    // size matches `size_of::<bpfland_tunables>()`.
+    let value = unsafe {
+        std::slice::from_raw_parts(
+            tunables as *const _ as *const u8,
+            std::mem::size_of::<bpf_intf::bpfland_tunables>(),
+        )
+    };
+    skel.maps
+        .tunables_map
+        .update(&key.to_ne_bytes(), value, libbpf_rs::MapFlags::ANY)
+        .context("Failed to update tunables_map")
+}
+
+/// Read a CPU's maximum frequency (in kHz) from cpufreq sysfs, used to turn the BPF side's
+/// utilization EWMA into an `avg_freq_khz` estimate. Returns 0 (ignored by the BPF side) if
+/// the CPU has no cpufreq sysfs entry, e.g. in some VMs.
+fn read_cpu_max_freq_khz(cpu: u32) -> u64 {
+    std::fs::read_to_string(format!(
+        "/sys/devices/system/cpu/cpu{}/cpufreq/cpuinfo_max_freq",
+        cpu
+    ))
+    .ok()
+    .and_then(|s| s.trim().parse().ok())
+    .unwrap_or(0)
+}
+
 fn is_smt_active() -> std::io::Result<i32> {
     let mut file = File::open("/sys/devices/system/cpu/smt/active")?;
     let mut contents = String::new();
@@ -159,6 +274,9 @@ struct Scheduler<'a> {
     skel: BpfSkel<'a>,
     struct_ops: Option<libbpf_rs::Link>,
     metrics: Metrics,
+    topology: Topology,
+    stats_server: Option<StatsServer>,
+    tunables: bpf_intf::bpfland_tunables,
 }
 
 impl<'a> Scheduler<'a> {
@@ -175,10 +293,11 @@ impl<'a> Scheduler<'a> {
             Err(e) => bail!("Failed to read SMT status: {}", e),
         };
         info!(
-            "{} {} {}",
+            "{} {} {} cpufreq={}",
             SCHEDULER_NAME,
             *build_id::SCX_FULL_VERSION,
-            if smt_enabled { "SMT on" } else { "SMT off" }
+            if smt_enabled { "SMT on" } else { "SMT off" },
+            opts.cpufreq
         );
 
         // Initialize BPF connector.
@@ -188,6 +307,35 @@ impl<'a> Scheduler<'a> {
 
         skel.struct_ops.bpfland_ops_mut().exit_dump_len = opts.exit_dump_len;
 
+        // Build the L3-cache-aware scheduling domains (or a single flat domain if topology
+        // awareness has been disabled).
+        let nr_cpus = libbpf_rs::num_possible_cpus().context("Failed to get the number of CPUs")?;
+        let topology = if opts.disable_topology {
+            Topology::flat(nr_cpus)
+        } else {
+            Topology::from_sysfs().unwrap_or_else(|e| {
+                log::warn!("Failed to probe L3 topology ({}), falling back to a flat domain", e);
+                Topology::flat(nr_cpus)
+            })
+        };
+        // domain ids double as DSQ ids and index fixed [MAX_DOMAINS] BPF arrays; a topology
+        // with more domains than that would collide with SHARED_DSQ_ID and run out of bounds.
+        let topology = if topology.nr_domains() > MAX_DOMAINS {
+            log::warn!(
+                "{} L3 domains exceeds the {} the BPF side can track, falling back to a flat domain",
+                topology.nr_domains(),
+                MAX_DOMAINS
+            );
+            Topology::flat(nr_cpus)
+        } else {
+            topology
+        };
+        info!(
+            "{} scheduling {}",
+            topology.nr_domains(),
+            if topology.nr_domains() == 1 { "domain" } else { "domains" }
+        );
+
         // Override default BPF scheduling parameters.
         skel.maps.rodata_data.debug = opts.debug;
         skel.maps.rodata_data.smt_enabled = smt_enabled;
@@ -197,9 +345,77 @@ impl<'a> Scheduler<'a> {
         skel.maps.rodata_data.slice_ns_lag = opts.slice_us_lag * 1000;
         skel.maps.rodata_data.starvation_thresh_ns = opts.starvation_thresh_us * 1000;
         skel.maps.rodata_data.nvcsw_thresh = opts.nvcsw_thresh;
+        skel.maps.rodata_data.nr_domains = topology.nr_domains() as u32;
+        skel.maps.rodata_data.cpufreq_util_pct = opts.cpufreq.utilization_pct();
+
+        // scx_bpf_select_cpu_dfl() only exists on newer kernels; fall back to a built-in path
+        // in main.bpf.c when it isn't available, rather than failing to load.
+        let has_select_cpu_dfl = scx_utils::compat::Feature::SelectCpuDfl.enabled();
+        if !has_select_cpu_dfl {
+            log::warn!("Kernel doesn't support scx_bpf_select_cpu_dfl(), using built-in fallback");
+        }
+        skel.maps.rodata_data.has_select_cpu_dfl = has_select_cpu_dfl;
+
+        // Centralized dispatch requires the infinite-slice dispatch path; fall back to the
+        // regular per-CPU scheduling path (and let the user know) if it isn't available.
+        let central_enabled = if opts.central {
+            let supported = scx_utils::compat::Feature::CentralDispatch.enabled();
+            if !supported {
+                log::warn!(
+                    "Kernel doesn't support centralized dispatch, falling back to per-CPU scheduling"
+                );
+            }
+            supported
+        } else {
+            false
+        };
+        if opts.central && (opts.central_cpu as usize) >= nr_cpus {
+            bail!(
+                "Invalid --central-cpu {}: system only has {} CPUs",
+                opts.central_cpu,
+                nr_cpus
+            );
+        }
+        if central_enabled {
+            info!("Centralized dispatch enabled on CPU {}", opts.central_cpu);
+        }
+        skel.maps.rodata_data.central_enabled = central_enabled;
+        skel.maps.rodata_data.central_cpu = opts.central_cpu;
 
         // Attach the scheduler.
         let mut skel = scx_ops_load!(skel, object, bpfland_ops, uei)?;
+
+        // Publish the cpu -> domain map so `enqueue()`/`dispatch()` can look up which
+        // per-domain DSQ a CPU (and the tasks that ran on it) belong to.
+        for (cpu, domain_id) in &topology.cpu_domain {
+            skel.maps
+                .cpu_domain
+                .update(&cpu.to_ne_bytes(), &domain_id.to_ne_bytes(), libbpf_rs::MapFlags::ANY)
+                .context("Failed to update cpu_domain map")?;
+        }
+
+        // Publish each CPU's max frequency so the BPF side can turn its utilization EWMA into
+        // an `avg_freq_khz` estimate.
+        for cpu in 0..nr_cpus as u32 {
+            let max_freq_khz = read_cpu_max_freq_khz(cpu);
+            skel.maps
+                .cpu_max_freq_khz
+                .update(&cpu.to_ne_bytes(), &max_freq_khz.to_ne_bytes(), libbpf_rs::MapFlags::ANY)
+                .context("Failed to update cpu_max_freq_khz map")?;
+        }
+
+        // Seed the live-tunable parameters. Unlike .rodata (frozen read-only once loaded),
+        // this map stays writable for the lifetime of the scheduler, so the stats/control
+        // socket can retune responsiveness without a restart (see Scheduler::apply_tunable()).
+        let tunables = bpf_intf::bpfland_tunables {
+            slice_ns: opts.slice_us * 1000,
+            slice_ns_min: opts.slice_us_min * 1000,
+            slice_ns_lag: opts.slice_us_lag * 1000,
+            nvcsw_thresh: opts.nvcsw_thresh,
+            starvation_thresh_ns: opts.starvation_thresh_us * 1000,
+        };
+        update_tunables_map(&skel, &tunables)?;
+
         let struct_ops = Some(scx_ops_attach!(skel, bpfland_ops)?);
 
         // Enable Prometheus metrics.
@@ -210,13 +426,116 @@ impl<'a> Scheduler<'a> {
                 .expect("failed to install Prometheus recorder");
         }
 
+        // Enable the stats/control socket, which can be used alongside Prometheus.
+        let stats_server = match &opts.stats_socket {
+            Some(path) => {
+                info!("Enabling stats socket: {}", path);
+                Some(StatsServer::new(path)?)
+            }
+            None => None,
+        };
+
         Ok(Self {
             skel,
             struct_ops,
             metrics: Metrics::new(),
+            topology,
+            stats_server,
+            tunables,
         })
     }
 
+    /// Balance load across domains by migrating a bounded number of recently active tasks
+    /// from the most overloaded domains to the most underloaded ones.
+    ///
+    /// Each domain maintains a running weighted average of runnable task load in
+    /// `bss_data.domain_load` (decayed by a quarter on the BPF side each time a task is enqueued
+    /// into the domain; it is not decayed on a timer or scaled by duty cycle). Only a handful of the most
+    /// recently active tasks per pushing domain are considered, keeping this O(1) per tick
+    /// regardless of how many tasks are running system-wide.
+    fn balance_domains(&mut self) -> Result<()> {
+        if self.topology.nr_domains() < 2 {
+            return Ok(());
+        }
+
+        let loads: Vec<(u32, u64)> = self
+            .topology
+            .domains
+            .iter()
+            .map(|d| (d.id, self.skel.maps.bss_data.domain_load[d.id as usize]))
+            .collect();
+        let mean = loads.iter().map(|(_, load)| *load).sum::<u64>() / loads.len() as u64;
+
+        let mut overloaded: Vec<&(u32, u64)> = loads.iter().filter(|(_, load)| *load > mean).collect();
+        let mut underloaded: Vec<&(u32, u64)> = loads.iter().filter(|(_, load)| *load < mean).collect();
+        overloaded.sort_by(|a, b| b.1.cmp(&a.1));
+        underloaded.sort_by(|a, b| a.1.cmp(&b.1));
+
+        for ((push_domain, _), (pull_domain, _)) in overloaded.iter().zip(underloaded.iter()) {
+            for pid in self.recent_tasks(*push_domain) {
+                self.skel
+                    .maps
+                    .task_target_domain
+                    .update(&pid.to_ne_bytes(), &pull_domain.to_ne_bytes(), libbpf_rs::MapFlags::ANY)
+                    .context("Failed to update task_target_domain map")?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Return up to `MAX_DOMAIN_MIGRATIONS` of the most recently active task pids in `domain`.
+    fn recent_tasks(&self, domain: u32) -> Vec<i32> {
+        self.skel.maps.bss_data.domain_recent_pids[domain as usize]
+            .iter()
+            .copied()
+            .filter(|&pid| pid != 0)
+            .take(MAX_DOMAIN_MIGRATIONS)
+            .collect()
+    }
+
+    fn snapshot(&self) -> StatsSnapshot {
+        StatsSnapshot {
+            nr_running: self.skel.maps.bss_data.nr_running,
+            nr_interactive: self.skel.maps.bss_data.nr_interactive,
+            nr_kthread_dispatches: self.skel.maps.bss_data.nr_kthread_dispatches,
+            nr_direct_dispatches: self.skel.maps.bss_data.nr_direct_dispatches,
+            nr_prio_dispatches: self.skel.maps.bss_data.nr_prio_dispatches,
+            nr_shared_dispatches: self.skel.maps.bss_data.nr_shared_dispatches,
+            avg_freq_khz: self.skel.maps.bss_data.avg_freq_khz,
+            nr_central_dispatches: self.skel.maps.bss_data.nr_central_dispatches,
+            nr_central_mismatches: self.skel.maps.bss_data.nr_central_mismatches,
+        }
+    }
+
+    /// Apply a tunable update received over the stats socket to `tunables_map`, the writable
+    /// BPF map the scheduler actually reads its live-tunable parameters from (`.rodata` is
+    /// frozen read-only by libbpf once the program is loaded, so it can't be retuned online).
+    fn apply_tunable(&mut self, tunable: Tunable) {
+        match tunable {
+            Tunable::SliceUs(v) => self.tunables.slice_ns = v * 1000,
+            Tunable::SliceUsMin(v) => self.tunables.slice_ns_min = v * 1000,
+            Tunable::SliceUsLag(v) => self.tunables.slice_ns_lag = v * 1000,
+            Tunable::NvcswThresh(v) => self.tunables.nvcsw_thresh = v,
+            Tunable::StarvationThreshUs(v) => self.tunables.starvation_thresh_ns = v * 1000,
+        }
+
+        if let Err(e) = update_tunables_map(&self.skel, &self.tunables) {
+            log::warn!("Failed to apply tunable update: {}", e);
+        }
+    }
+
+    /// Poll the stats socket (if enabled) and apply any tunable updates it received.
+    fn poll_stats_server(&mut self) {
+        let Some(stats_server) = self.stats_server.as_ref() else {
+            return;
+        };
+        let tunables = stats_server.poll(self.snapshot());
+        for tunable in tunables {
+            self.apply_tunable(tunable);
+        }
+    }
+
     fn update_stats(&mut self) {
         let nr_cpus = self.skel.maps.bss_data.nr_online_cpus;
         let nr_running = self.skel.maps.bss_data.nr_running;
@@ -225,6 +544,9 @@ impl<'a> Scheduler<'a> {
         let nr_direct_dispatches = self.skel.maps.bss_data.nr_direct_dispatches;
         let nr_prio_dispatches = self.skel.maps.bss_data.nr_prio_dispatches;
         let nr_shared_dispatches = self.skel.maps.bss_data.nr_shared_dispatches;
+        let avg_freq_khz = self.skel.maps.bss_data.avg_freq_khz;
+        let nr_central_dispatches = self.skel.maps.bss_data.nr_central_dispatches;
+        let nr_central_mismatches = self.skel.maps.bss_data.nr_central_mismatches;
 
         // Update Prometheus statistics.
         self.metrics
@@ -245,16 +567,26 @@ impl<'a> Scheduler<'a> {
         self.metrics
             .nr_shared_dispatches
             .set(nr_shared_dispatches as f64);
+        self.metrics.avg_freq_khz.set(avg_freq_khz as f64);
+        self.metrics
+            .nr_central_dispatches
+            .set(nr_central_dispatches as f64);
+        self.metrics
+            .nr_central_mismatches
+            .set(nr_central_mismatches as f64);
 
         // Log scheduling statistics.
-        info!("running: {:>4}/{:<4} interactive: {:>4} | kthread: {:<6} | direct: {:<6} | prio: {:<6} | shared: {:<6}",
+        info!("running: {:>4}/{:<4} interactive: {:>4} | kthread: {:<6} | direct: {:<6} | prio: {:<6} | shared: {:<6} | freq: {:<7} | central: {:<6} | mismatch: {:<6}",
             nr_running,
             nr_cpus,
             nr_interactive,
             nr_kthread_dispatches,
             nr_direct_dispatches,
             nr_prio_dispatches,
-            nr_shared_dispatches);
+            nr_shared_dispatches,
+            avg_freq_khz,
+            nr_central_dispatches,
+            nr_central_mismatches);
     }
 
     pub fn exited(&mut self) -> bool {
@@ -264,6 +596,10 @@ impl<'a> Scheduler<'a> {
     fn run(&mut self, shutdown: Arc<AtomicBool>) -> Result<UserExitInfo> {
         while !shutdown.load(Ordering::Relaxed) && !self.exited() {
             self.update_stats();
+            if let Err(e) = self.balance_domains() {
+                log::warn!("Failed to balance domains: {}", e);
+            }
+            self.poll_stats_server();
             std::thread::sleep(Duration::from_millis(1000));
         }
         self.update_stats();
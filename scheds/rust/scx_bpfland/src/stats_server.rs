@@ -0,0 +1,153 @@
+// SPDX-License-Identifier: GPL-2.0
+//
+// Copyright (c) 2024 Andrea Righi <righi.andrea@gmail.com>.
+
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+//! A Unix domain socket control interface.
+//!
+//! Connecting and sending `stats` returns the current scheduler statistics as a single
+//! line of JSON. Sending `<tunable>=<value>` (e.g. `slice_us=3000`) updates that tunable at
+//! runtime, without requiring a restart.
+
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Write;
+use std::os::unix::net::UnixListener;
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::Context;
+use anyhow::Result;
+
+/// How long a single read from a connection may block before the connection is dropped.
+///
+/// `UnixListener::accept()` always hands back a *blocking* stream regardless of the listener's
+/// own non-blocking mode, so without this a slow or silent client would stall the scheduler's
+/// single-threaded `run()` loop (stats reporting, domain balancing) indefinitely.
+const CLIENT_READ_TIMEOUT: Duration = Duration::from_millis(50);
+
+/// A runtime-tunable scheduler parameter that can be updated over the stats socket.
+#[derive(Debug, Clone, Copy)]
+pub enum Tunable {
+    SliceUs(u64),
+    SliceUsMin(u64),
+    SliceUsLag(u64),
+    NvcswThresh(u64),
+    StarvationThreshUs(u64),
+}
+
+/// A point-in-time snapshot of scheduler statistics, serialized as a single line of JSON.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct StatsSnapshot {
+    pub nr_running: u64,
+    pub nr_interactive: u64,
+    pub nr_kthread_dispatches: u64,
+    pub nr_direct_dispatches: u64,
+    pub nr_prio_dispatches: u64,
+    pub nr_shared_dispatches: u64,
+    pub avg_freq_khz: u64,
+    pub nr_central_dispatches: u64,
+    pub nr_central_mismatches: u64,
+}
+
+impl StatsSnapshot {
+    fn to_json(self) -> String {
+        format!(
+            "{{\"nr_running\":{},\"nr_interactive\":{},\"nr_kthread_dispatches\":{},\
+             \"nr_direct_dispatches\":{},\"nr_prio_dispatches\":{},\"nr_shared_dispatches\":{},\
+             \"avg_freq_khz\":{},\"nr_central_dispatches\":{},\"nr_central_mismatches\":{}}}",
+            self.nr_running,
+            self.nr_interactive,
+            self.nr_kthread_dispatches,
+            self.nr_direct_dispatches,
+            self.nr_prio_dispatches,
+            self.nr_shared_dispatches,
+            self.avg_freq_khz,
+            self.nr_central_dispatches,
+            self.nr_central_mismatches,
+        )
+    }
+}
+
+/// A non-blocking control socket serving [`StatsSnapshot`]s and accepting [`Tunable`] updates.
+pub struct StatsServer {
+    listener: UnixListener,
+}
+
+impl StatsServer {
+    pub fn new(path: &str) -> Result<Self> {
+        let _ = std::fs::remove_file(path);
+        let listener = UnixListener::bind(Path::new(path))
+            .with_context(|| format!("Failed to bind stats socket at {}", path))?;
+        listener
+            .set_nonblocking(true)
+            .context("Failed to set stats socket non-blocking")?;
+
+        Ok(Self { listener })
+    }
+
+    /// Service any connections that arrived since the last call, replying to `stats` requests
+    /// with `snapshot` and returning any tunable updates that were requested.
+    pub fn poll(&self, snapshot: StatsSnapshot) -> Vec<Tunable> {
+        let mut tunables = Vec::new();
+
+        loop {
+            let stream = match self.listener.accept() {
+                Ok((stream, _)) => stream,
+                Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => break,
+                Err(_) => break,
+            };
+            Self::handle_connection(stream, snapshot, &mut tunables);
+        }
+
+        tunables
+    }
+
+    fn handle_connection(stream: UnixStream, snapshot: StatsSnapshot, tunables: &mut Vec<Tunable>) {
+        if stream.set_read_timeout(Some(CLIENT_READ_TIMEOUT)).is_err() {
+            return;
+        }
+        let Ok(reader_stream) = stream.try_clone() else {
+            return;
+        };
+        let mut reader = BufReader::new(reader_stream);
+        let mut writer = stream;
+
+        let mut line = String::new();
+        if reader.read_line(&mut line).is_err() {
+            return;
+        }
+
+        match line.trim() {
+            "stats" => {
+                let _ = writeln!(writer, "{}", snapshot.to_json());
+            }
+            cmd => match parse_tunable(cmd) {
+                Some(tunable) => {
+                    tunables.push(tunable);
+                    let _ = writeln!(writer, "{{\"ok\":true}}");
+                }
+                None => {
+                    let _ = writeln!(writer, "{{\"ok\":false,\"error\":\"unknown command\"}}");
+                }
+            },
+        }
+    }
+}
+
+fn parse_tunable(cmd: &str) -> Option<Tunable> {
+    let (key, value) = cmd.split_once('=')?;
+    let value: u64 = value.trim().parse().ok()?;
+
+    match key.trim() {
+        "slice_us" => Some(Tunable::SliceUs(value)),
+        "slice_us_min" => Some(Tunable::SliceUsMin(value)),
+        "slice_us_lag" => Some(Tunable::SliceUsLag(value)),
+        "nvcsw_thresh" => Some(Tunable::NvcswThresh(value)),
+        "starvation_thresh_us" => Some(Tunable::StarvationThreshUs(value)),
+        _ => None,
+    }
+}